@@ -22,12 +22,37 @@ pub struct Transform {
     height: u16,
     left: u16,
     top: u16,
+    // Crop rectangle in source-pixel space. `crop_width`/`crop_height` equal `src_width`/
+    // `src_height` when the item isn't cropped (e.g. `FitStrategy::Contain`).
+    crop_left: u16,
+    crop_top: u16,
+    crop_width: u16,
+    crop_height: u16,
+}
+
+/// How an item's source image should be fit into its laid-out cell, mirroring the CSS
+/// `object-fit` keywords. `Cover` and `FixedAspect` crop the source (reported back via
+/// `Transform`'s `crop_*` fields) so the front-end can render a clean `object-fit: cover`
+/// without distorting the image; `Contain` never crops.
+#[derive(Clone, Copy)]
+pub enum FitStrategy {
+    /// Scale down to fit entirely inside the cell; no cropping.
+    Contain,
+    /// Crop the source to the cell's own aspect ratio, then scale to fill it exactly.
+    Cover,
+    /// Crop the source to a fixed `width / height` ratio, then scale to fill the cell.
+    FixedAspect(u32, u32),
 }
 
 #[wasm_bindgen]
 pub struct Layout {
     items: Vec<Transform>,
     thumbnail_size: u32,
+    section_header_tops: Vec<u32>,
+    // Index range of items actually computed by the most recent `compute_range` call; items
+    // outside of it keep whatever stale `Transform` they had before and shouldn't be rendered.
+    computed_range_start: usize,
+    computed_range_end: usize,
 }
 
 /// Public methods, exported to JavaScript.
@@ -45,10 +70,17 @@ impl Layout {
                     height: 0,
                     left: 0,
                     top: 0,
+                    crop_left: 0,
+                    crop_top: 0,
+                    crop_width: 0,
+                    crop_height: 0,
                 };
                 length
             ],
             thumbnail_size,
+            section_header_tops: Vec::new(),
+            computed_range_start: 0,
+            computed_range_end: 0,
         }
     }
 
@@ -84,17 +116,31 @@ impl Layout {
         // TODO: Look up proper masonry algorithm, e.g. https://euler.stephan-brumme.com/215/
 
         // Could crop images with extreme aspect ratios (e.g. > 4:1) for easier layouting
+        self.fill_justified_rows(0..self.items.len(), container_width, padding, 0) as i32
+    }
 
+    /// Greedy justified row-filling over `range`: append items to a row until it overflows
+    /// `container_width` (the overflowing item stays in that row), then rescale the row's height
+    /// so it fills `container_width` exactly, starting at `top_offset`.
+    /// Returns the `top_offset` for whatever comes after `range`. Shared by `compute` (the whole
+    /// list is one range) and `compute_sectioned` (one range per date/month group).
+    fn fill_justified_rows(
+        &mut self,
+        range: std::ops::Range<usize>,
+        container_width: u32,
+        padding: u32,
+        top_offset: u32,
+    ) -> u32 {
         let base_row_height = self.thumbnail_size as u16;
 
-        let mut top_offset = 0;
+        let mut top_offset = top_offset;
         let mut cur_row_width = 0;
-        let mut first_row_item_index = 0;
+        let mut first_row_item_index = range.start;
 
-        for i in 0..self.items.len() {
+        for i in range.clone() {
             let item = &mut self.items[i];
             let rel_width =
-                (base_row_height as f32 / item.src_height as f32) * item.src_width as f32;
+                base_row_height as f32 * safe_aspect_ratio(item.src_width, item.src_height);
             item.width = rel_width as u16;
             item.top = top_offset as u16;
             item.height = base_row_height;
@@ -108,39 +154,353 @@ impl Layout {
                 // If it exceeds it, position all current items in the row accordingly and start a new row for this item
                 // Position all items in this row properly after the row is filled, needs to expand a little
 
-                // Now that the size of this row is definitive: Set the actual size of all row items
-                let correction_factor = container_width as f32 / new_row_width as f32;
+                // Now that the size of this row is definitive: solve for the row height that
+                // makes the row's items exactly fill `container_width` (the same "widths + (n-1)
+                // gaps" convention `distribute_row_widths` targets — using the overflow check's
+                // `n`-paddings count here instead would leave `distribute_row_widths` trying to
+                // hit a target the natural widths can never reach, so it could only ever pad
+                // rows out, never trim an overshoot). Widths are floored and then the leftover
+                // pixels (from rounding every item down) are handed out one-by-one via the
+                // largest-remainder method, so the row's items sum up to exactly
+                // `container_width` instead of leaving a ragged gap at the right edge.
+                let row_range = first_row_item_index..=i;
+                let row_height =
+                    self.solve_row_height(first_row_item_index, i, container_width, padding, base_row_height);
 
-                item.left = (item.left as f32 * correction_factor) as u16;
-                item.width = (item.width as f32 * correction_factor) as u16;
-                item.height = (item.height as f32 * correction_factor) as u16;
+                let natural_widths: Vec<f32> = row_range
+                    .clone()
+                    .map(|k| {
+                        let it = &self.items[k];
+                        safe_aspect_ratio(it.src_width, it.src_height) * row_height
+                    })
+                    .collect();
+                let widths = distribute_row_widths(&natural_widths, container_width, padding);
+                let lefts = positions_from_widths(&widths, padding);
 
-                for j in first_row_item_index..i {
-                    let prev_item = &mut self.items[j];
-                    prev_item.left = (prev_item.left as f32 * correction_factor) as u16;
-                    prev_item.width = (prev_item.width as f32 * correction_factor) as u16;
-                    prev_item.height = (prev_item.height as f32 * correction_factor) as u16;
+                for (offset, k) in row_range.enumerate() {
+                    let row_item = &mut self.items[k];
+                    row_item.width = widths[offset];
+                    row_item.left = lefts[offset];
+                    row_item.height = row_height as u16;
+                    row_item.crop_left = 0;
+                    row_item.crop_top = 0;
+                    row_item.crop_width = row_item.src_width;
+                    row_item.crop_height = row_item.src_height;
                 }
 
                 // Start a new row
                 cur_row_width = 0;
-                first_row_item_index = (i + 1);
-                top_offset += padding as u32 + (base_row_height as f32 * correction_factor) as u32;
+                first_row_item_index = i + 1;
+                top_offset += padding + row_height as u32;
             } else {
                 cur_row_width = new_row_width;
             }
         }
-        // Return the height of the container: If a new row was just started, no need to add last item's height
+        // If a new row was just started, no need to add the last item's height
         if cur_row_width != 0 {
-            let last_item = self.items.last();
-            return match last_item {
-                Some(item) => top_offset as i32 + item.height as i32,
-                None => top_offset as i32,
-            };
+            if let Some(last_index) = range.last() {
+                // The unfinished trailing row isn't rescaled, and isn't cropped either.
+                for k in first_row_item_index..=last_index {
+                    let row_item = &mut self.items[k];
+                    row_item.crop_left = 0;
+                    row_item.crop_top = 0;
+                    row_item.crop_width = row_item.src_width;
+                    row_item.crop_height = row_item.src_height;
+                }
+                return top_offset + self.items[last_index].height as u32;
+            }
         }
+        top_offset
+    }
+
+    /// Google Photos-style sectioned masonry: lays out each section (e.g. a day or month of
+    /// photos) as its own independent justified block via `fill_justified_rows`, inserting a
+    /// `header_height`-tall gap before every section's first row so items from one section never
+    /// share a row with the next. `section_starts` holds each section's first item index and must
+    /// start at `0` and be sorted ascending; the `top` offset of each header band is written to
+    /// `section_header_tops` (read it back via `section_header_tops()`/`section_count()`).
+    pub fn compute_sectioned(
+        &mut self,
+        container_width: u32,
+        padding: u32,
+        section_starts: &[u32],
+        header_height: u32,
+    ) -> i32 {
+        if self.items.is_empty() || section_starts.is_empty() {
+            self.section_header_tops.clear();
+            return 0;
+        }
+
+        let mut header_tops = Vec::with_capacity(section_starts.len());
+        let mut top_offset = 0u32;
+
+        for (section_index, &start) in section_starts.iter().enumerate() {
+            // Clamp against `self.items.len()`: a stale (e.g. post-delete) `section_starts` entry
+            // past the current item count would otherwise have `fill_justified_rows` index past
+            // the end of `self.items` and panic.
+            let start = (start as usize).min(self.items.len());
+            let end = section_starts
+                .get(section_index + 1)
+                .map(|&next| next as usize)
+                .unwrap_or(self.items.len())
+                .min(self.items.len())
+                .max(start);
+
+            header_tops.push(top_offset);
+            top_offset += header_height;
+            top_offset = self.fill_justified_rows(start..end, container_width, padding, top_offset);
+        }
+
+        self.section_header_tops = header_tops;
         top_offset as i32
     }
 
+    /// Pointer to the `top` offset of each section header band computed by the most recent
+    /// `compute_sectioned` call, one entry per `section_starts` index.
+    pub fn section_header_tops(&self) -> *const u32 {
+        self.section_header_tops.as_ptr()
+    }
+
+    /// Number of entries available via `section_header_tops()`.
+    pub fn section_count(&self) -> usize {
+        self.section_header_tops.len()
+    }
+
+    /// Viewport-windowed justified layout for very large collections: cheaply figures out the
+    /// full container height and every row's `top`/height in one O(n) pass that only reads
+    /// `src_width`/`src_height` (no per-item geometry, no width stretching), then does the
+    /// expensive per-item layout via `fill_justified_rows` only for rows overlapping
+    /// `[viewport_top - OVERSCAN_PX, viewport_bottom + OVERSCAN_PX]`. Items outside that window
+    /// keep whatever `Transform` they had before; call `computed_range_start`/
+    /// `computed_range_end` to learn which items actually got fresh geometry, so JS only mounts
+    /// DOM nodes for those.
+    pub fn compute_range(
+        &mut self,
+        container_width: u32,
+        padding: u32,
+        viewport_top: u32,
+        viewport_bottom: u32,
+    ) -> i32 {
+        const OVERSCAN_PX: u32 = 300;
+
+        let (rows, total_height) = self.row_bounds(0..self.items.len(), container_width, padding, 0);
+
+        let window_top = viewport_top.saturating_sub(OVERSCAN_PX);
+        let window_bottom = viewport_bottom.saturating_add(OVERSCAN_PX);
+
+        let mut computed_start = self.items.len();
+        let mut computed_end = 0;
+
+        for (start, end, top, height) in rows {
+            if top + height < window_top || top > window_bottom {
+                continue;
+            }
+            self.fill_justified_rows(start..end, container_width, padding, top);
+            computed_start = computed_start.min(start);
+            computed_end = computed_end.max(end);
+        }
+
+        let (computed_start, computed_end) = if computed_end > computed_start {
+            (computed_start, computed_end)
+        } else {
+            (0, 0)
+        };
+        self.computed_range_start = computed_start;
+        self.computed_range_end = computed_end;
+
+        total_height as i32
+    }
+
+    /// Start index (inclusive) of the items last computed by `compute_range`.
+    pub fn computed_range_start(&self) -> usize {
+        self.computed_range_start
+    }
+
+    /// End index (exclusive) of the items last computed by `compute_range`.
+    pub fn computed_range_end(&self) -> usize {
+        self.computed_range_end
+    }
+
+    /// Row height that makes items `first_row_item_index..=last_row_item_index` exactly fill
+    /// `container_width` under the "widths + (n-1) gaps" convention `distribute_row_widths`
+    /// targets, guarding against zero/garbage source dimensions via `safe_aspect_ratio` so a
+    /// single poisoned item can't turn this into `inf`/`NaN`. Shared by `fill_justified_rows`
+    /// (which also stretches and positions the row) and `row_bounds` (which only needs the
+    /// height to estimate row `top`s cheaply) so the two passes can never disagree on a row's
+    /// height.
+    fn solve_row_height(
+        &self,
+        first_row_item_index: usize,
+        last_row_item_index: usize,
+        container_width: u32,
+        padding: u32,
+        base_row_height: u16,
+    ) -> f32 {
+        let gaps = (last_row_item_index - first_row_item_index) as f32;
+        let ar_sum: f32 = (first_row_item_index..=last_row_item_index)
+            .map(|k| safe_aspect_ratio(self.items[k].src_width, self.items[k].src_height))
+            .sum();
+        if ar_sum > 0.0 {
+            ((container_width as f32 - gaps * padding as f32) / ar_sum).max(1.0)
+        } else {
+            base_row_height as f32
+        }
+    }
+
+    /// Cheap O(n) version of `fill_justified_rows`'s row-breaking decision: figures out each
+    /// row's item range, `top` and height without writing any `Transform` fields or distributing
+    /// pixel widths. Returns the discovered rows plus the total height of `range`, starting at
+    /// `top_offset`.
+    fn row_bounds(
+        &self,
+        range: std::ops::Range<usize>,
+        container_width: u32,
+        padding: u32,
+        top_offset: u32,
+    ) -> (Vec<(usize, usize, u32, u32)>, u32) {
+        let base_row_height = self.thumbnail_size as u16;
+
+        let mut top_offset = top_offset;
+        let mut cur_row_width = 0;
+        let mut first_row_item_index = range.start;
+        let mut rows = Vec::new();
+
+        for i in range.clone() {
+            let it = &self.items[i];
+            let rel_width = base_row_height as f32 * safe_aspect_ratio(it.src_width, it.src_height);
+            let new_row_width = cur_row_width + rel_width as u32 + padding;
+
+            if new_row_width > container_width {
+                let row_height =
+                    self.solve_row_height(first_row_item_index, i, container_width, padding, base_row_height) as u32;
+
+                rows.push((first_row_item_index, i + 1, top_offset, row_height));
+
+                cur_row_width = 0;
+                first_row_item_index = i + 1;
+                top_offset += padding + row_height;
+            } else {
+                cur_row_width = new_row_width;
+            }
+        }
+        // Matches `fill_justified_rows`: an unfinished trailing row keeps the uncorrected row
+        // height and isn't followed by another padding gap.
+        if cur_row_width != 0 {
+            if let Some(last_index) = range.last() {
+                let row_height = base_row_height as u32;
+                rows.push((first_row_item_index, last_index + 1, top_offset, row_height));
+                top_offset += row_height;
+            }
+        }
+
+        (rows, top_offset)
+    }
+
+    /// Optimal justified layout: instead of greedily filling rows until they overflow and then
+    /// stretching whatever ended up in the row (which can leave row heights far from
+    /// `thumbnail_size`), this picks row breaks that minimize the total deviation from the
+    /// target row height across the whole list.
+    ///
+    /// This is a Knuth-Plass-style shortest-path: for every candidate row `j..i` we compute the
+    /// height that makes the row's items exactly fill `container_width`, score it with
+    /// `row_badness`, and find the break points that minimize the summed badness. The search
+    /// window is bounded (`OPTIMAL_ROW_WINDOW`) since real rows rarely hold more than a handful
+    /// of items, keeping this close to linear in the number of items.
+    pub fn compute_justified_optimal(
+        &mut self,
+        container_width: u32,
+        padding: u32,
+        max_row_height: u32,
+    ) -> i32 {
+        let n = self.items.len();
+        if n == 0 {
+            return 0;
+        }
+
+        let target = self.thumbnail_size as f32;
+        let container_width = container_width as f32;
+        let padding = padding as f32;
+        let max_row_height = max_row_height as f32;
+
+        let aspect_ratios: Vec<f32> = self
+            .items
+            .iter()
+            .map(|item| safe_aspect_ratio(item.src_width, item.src_height))
+            .collect();
+
+        // best[i] = lowest total badness to lay out items 0..i, using a row ending at i
+        // break_at[i] = start index of that last row
+        let mut best = vec![f32::INFINITY; n + 1];
+        let mut break_at = vec![0usize; n + 1];
+        best[0] = 0.0;
+
+        for i in 1..=n {
+            let lower = i.saturating_sub(OPTIMAL_ROW_WINDOW);
+            for j in lower..i {
+                if best[j].is_infinite() {
+                    continue;
+                }
+                let ar_sum: f32 = aspect_ratios[j..i].iter().sum();
+                if ar_sum <= 0.0 {
+                    continue;
+                }
+                let gaps = (i - j - 1) as f32;
+                let row_height = (container_width - gaps * padding) / ar_sum;
+                if row_height <= 0.0 {
+                    continue;
+                }
+                let cost = best[j] + row_badness(row_height, target, max_row_height);
+                if cost < best[i] {
+                    best[i] = cost;
+                    break_at[i] = j;
+                }
+            }
+        }
+
+        // Reconstruct the chosen row breaks by walking backwards from the last item.
+        let mut rows = Vec::new();
+        let mut i = n;
+        while i > 0 {
+            let j = break_at[i];
+            rows.push((j, i));
+            i = j;
+        }
+        rows.reverse();
+
+        let container_width_px = container_width as u32;
+        let padding_px = padding as u32;
+
+        let mut top_offset = 0.0f32;
+        for (j, i) in rows {
+            let ar_sum: f32 = aspect_ratios[j..i].iter().sum();
+            let gaps = (i - j - 1) as f32;
+            let row_height = ((container_width - gaps * padding) / ar_sum).max(1.0);
+
+            let natural_widths: Vec<f32> = aspect_ratios[j..i]
+                .iter()
+                .map(|&ar| ar * row_height)
+                .collect();
+            let widths = distribute_row_widths(&natural_widths, container_width_px, padding_px);
+            let lefts = positions_from_widths(&widths, padding_px);
+
+            for (offset, &w) in widths.iter().enumerate() {
+                let item = &mut self.items[j + offset];
+                item.top = top_offset as u16;
+                item.left = lefts[offset];
+                item.height = row_height as u16;
+                item.width = w;
+                item.crop_left = 0;
+                item.crop_top = 0;
+                item.crop_width = item.src_width;
+                item.crop_height = item.src_height;
+            }
+
+            top_offset += row_height + padding;
+        }
+
+        (top_offset - padding).max(0.0) as i32
+    }
+
     pub fn compute_vertical(&mut self, container_width: u32, padding: u16) -> i32 {
         // Main idea: Initialize with N columns of identical widths
         // loop over images, put them in the column that has the least height filled
@@ -152,7 +512,7 @@ impl Layout {
             return 0;
         }
 
-        let col_width = (0.5 + (container_width as f32 / n_columns as f32)) as u16;
+        let (col_widths, col_lefts) = distribute_column_widths(container_width, n_columns as u32);
 
         let mut col_heights: Vec<i32> = vec![0; n_columns as usize];
 
@@ -166,16 +526,21 @@ impl Layout {
                 h = h / max_aspect_ratio;
             }
 
-            item.width = col_width - padding;
-            item.height = ((item.width as f32 / item.src_width as f32) * h as f32 + 0.5) as u16;
-
             let shortest_col_index = match index_of_min(&col_heights) {
                 Some(index) => index,
                 None => 0,
             };
 
+            item.width = col_widths[shortest_col_index] - padding;
+            item.height = ((item.width as f32 / item.src_width as f32) * h as f32 + 0.5) as u16;
+
             item.top = col_heights[shortest_col_index] as u16;
-            item.left = (shortest_col_index as u16 * col_width) as u16;
+            item.left = col_lefts[shortest_col_index];
+
+            item.crop_left = 0;
+            item.crop_top = 0;
+            item.crop_width = item.src_width;
+            item.crop_height = item.src_height;
 
             col_heights[shortest_col_index] += item.height as i32 + padding as i32;
         }
@@ -186,6 +551,315 @@ impl Layout {
             None => 0,
         }
     }
+
+    /// Variant of `compute_vertical` that follows up the greedy "place in shortest column" pass
+    /// with a bounded local-improvement pass: repeatedly take the last-placed item out of the
+    /// tallest column and move it to the shortest column if that reduces the max-minus-min
+    /// column-height spread, stopping once no move helps (or after `MAX_BALANCE_ITERATIONS`).
+    /// This tightens the ragged bottom edge that pure greedy placement leaves on small/medium
+    /// collections, without needing a full bin-packing solver.
+    pub fn compute_vertical_balanced(&mut self, container_width: u32, padding: u16) -> i32 {
+        let max_aspect_ratio = 3.; // X times as wide as narrow or vice versa
+
+        let n_columns = (0.5 + (container_width as f32 / self.thumbnail_size as f32)) as i32;
+        if n_columns == 0 {
+            return 0;
+        }
+        let n_columns = n_columns as usize;
+
+        let (col_widths, col_lefts) = distribute_column_widths(container_width, n_columns as u32);
+
+        let mut col_heights: Vec<i32> = vec![0; n_columns];
+        let mut columns: Vec<Vec<usize>> = vec![Vec::new(); n_columns];
+
+        for i in 0..self.items.len() {
+            let mut h = self.items[i].src_height as f32;
+            let aspect_ratio = self.items[i].src_width as f32 / h;
+            if aspect_ratio > max_aspect_ratio {
+                h *= max_aspect_ratio;
+            } else if aspect_ratio < max_aspect_ratio / 5. {
+                h /= max_aspect_ratio;
+            }
+
+            let shortest_col_index = index_of_min(&col_heights).unwrap_or(0);
+
+            let item = &mut self.items[i];
+            item.width = col_widths[shortest_col_index] - padding;
+            item.height = ((item.width as f32 / item.src_width as f32) * h + 0.5) as u16;
+            item.top = col_heights[shortest_col_index] as u16;
+            item.left = col_lefts[shortest_col_index];
+            item.crop_left = 0;
+            item.crop_top = 0;
+            item.crop_width = item.src_width;
+            item.crop_height = item.src_height;
+
+            col_heights[shortest_col_index] += item.height as i32 + padding as i32;
+            columns[shortest_col_index].push(i);
+        }
+
+        const MAX_BALANCE_ITERATIONS: usize = 64;
+        for _ in 0..MAX_BALANCE_ITERATIONS {
+            let tallest = index_of_max(&col_heights).unwrap_or(0);
+            let shortest = index_of_min(&col_heights).unwrap_or(0);
+            if tallest == shortest {
+                break;
+            }
+
+            let moved_item_index = match columns[tallest].last() {
+                Some(&index) => index,
+                None => break,
+            };
+            let moved_extent = self.items[moved_item_index].height as i32 + padding as i32;
+
+            let mut hypothetical = col_heights.clone();
+            hypothetical[tallest] -= moved_extent;
+            hypothetical[shortest] += moved_extent;
+
+            let current_spread = col_heights.iter().max().unwrap() - col_heights.iter().min().unwrap();
+            let hypothetical_spread =
+                hypothetical.iter().max().unwrap() - hypothetical.iter().min().unwrap();
+            if hypothetical_spread >= current_spread {
+                break;
+            }
+
+            columns[tallest].pop();
+            // Re-sort by original index so item order within a column stays stable.
+            let insert_at = columns[shortest].partition_point(|&index| index < moved_item_index);
+            columns[shortest].insert(insert_at, moved_item_index);
+
+            col_heights[tallest] =
+                self.relayout_column(&columns[tallest], col_widths[tallest], col_lefts[tallest], padding);
+            col_heights[shortest] = self.relayout_column(
+                &columns[shortest],
+                col_widths[shortest],
+                col_lefts[shortest],
+                padding,
+            );
+        }
+
+        col_heights.iter().max().copied().unwrap_or(0)
+    }
+
+    /// Repositions `item_indices` (already sorted by original index) as a single column of width
+    /// `col_width - padding` at `left`, stacked top to bottom. Returns the column's new height.
+    /// Used by `compute_vertical_balanced` to re-layout a column after a rebalancing move.
+    fn relayout_column(
+        &mut self,
+        item_indices: &[usize],
+        col_width: u16,
+        left: u16,
+        padding: u16,
+    ) -> i32 {
+        let new_width = col_width - padding;
+        let mut top = 0i32;
+        for &index in item_indices {
+            let item = &mut self.items[index];
+            if item.width != new_width && item.width > 0 {
+                item.height = ((item.height as f32 * new_width as f32) / item.width as f32).round() as u16;
+            }
+            item.width = new_width;
+            item.left = left;
+            item.top = top as u16;
+            top += item.height as i32 + padding as i32;
+        }
+        top
+    }
+
+    /// Variant of `compute_vertical` that lays out items using an explicit `FitStrategy` instead
+    /// of the ad-hoc extreme-aspect-ratio clamp, reporting each item's crop rectangle (source-
+    /// pixel space, via `Transform`'s `crop_*` fields) so the front-end can render a clean
+    /// `object-fit: cover` without distorting the image.
+    ///
+    /// `fit_mode` selects the strategy (`0` = Contain, `1` = Cover, `2` = FixedAspect using
+    /// `fixed_aspect_width`/`fixed_aspect_height`) since wasm-bindgen can't pass a Rust enum
+    /// carrying data across the JS boundary.
+    pub fn compute_vertical_fit(
+        &mut self,
+        container_width: u32,
+        padding: u16,
+        fit_mode: u8,
+        fixed_aspect_width: u32,
+        fixed_aspect_height: u32,
+    ) -> i32 {
+        let fit = match fit_mode {
+            1 => FitStrategy::Cover,
+            2 => FitStrategy::FixedAspect(fixed_aspect_width, fixed_aspect_height),
+            _ => FitStrategy::Contain,
+        };
+
+        let n_columns = (0.5 + (container_width as f32 / self.thumbnail_size as f32)) as i32;
+        if n_columns == 0 {
+            return 0;
+        }
+
+        let (col_widths, col_lefts) = distribute_column_widths(container_width, n_columns as u32);
+
+        let mut col_heights: Vec<i32> = vec![0; n_columns as usize];
+
+        for item in self.items.iter_mut() {
+            let shortest_col_index = index_of_min(&col_heights).unwrap_or(0);
+            let cell_width = col_widths[shortest_col_index] - padding;
+
+            let (crop_left, crop_top, crop_width, crop_height, cell_height) = match fit {
+                FitStrategy::Contain => {
+                    // `safe_aspect_ratio`'s args are swapped here on purpose: this arm divides by
+                    // `src_width` (to scale by height/width instead of width/height), so the
+                    // guarded denominator needs to be `src_width`.
+                    let h = cell_width as f32 * safe_aspect_ratio(item.src_height, item.src_width);
+                    (0, 0, item.src_width, item.src_height, h as u16)
+                }
+                FitStrategy::Cover => {
+                    let target_ar = cell_width as f32 / self.thumbnail_size as f32;
+                    let (cl, ct, cw, ch) = centered_crop(item.src_width, item.src_height, target_ar);
+                    (cl, ct, cw, ch, self.thumbnail_size as u16)
+                }
+                FitStrategy::FixedAspect(w, h) => {
+                    let target_ar = w as f32 / h as f32;
+                    let (cl, ct, cw, ch) = centered_crop(item.src_width, item.src_height, target_ar);
+                    (cl, ct, cw, ch, (cell_width as f32 / target_ar) as u16)
+                }
+            };
+
+            item.crop_left = crop_left;
+            item.crop_top = crop_top;
+            item.crop_width = crop_width;
+            item.crop_height = crop_height;
+            item.width = cell_width;
+            item.height = cell_height;
+            item.top = col_heights[shortest_col_index] as u16;
+            item.left = col_lefts[shortest_col_index];
+
+            col_heights[shortest_col_index] += item.height as i32 + padding as i32;
+        }
+
+        col_heights.iter().max().copied().unwrap_or(0)
+    }
+}
+
+// Candidate rows rarely hold more than a dozen or so thumbnails, so bounding the
+// dynamic-programming window keeps `compute_justified_optimal` close to linear.
+const OPTIMAL_ROW_WINDOW: usize = 15;
+
+/// Aspect ratio (`src_width / src_height`) used by the row-breaking algorithms. An item with
+/// `src_height == 0` (every item starts this way until `set_item_input` has been called for it,
+/// and corrupt source images can also report it) would otherwise divide out to `inf`, and an
+/// `inf`/`NaN` entry poisons every row-width sum it's folded into — in `compute_justified_optimal`
+/// that makes `best[]` stay infinite for the rest of the list, not just the offending row.
+/// Falling back to `1.0` keeps such an item a well-behaved (if visually wrong until its real size
+/// arrives) square instead of infecting every row after it.
+fn safe_aspect_ratio(src_width: u16, src_height: u16) -> f32 {
+    if src_height == 0 {
+        1.0
+    } else {
+        src_width as f32 / src_height as f32
+    }
+}
+
+/// Badness of stretching a row to `height` when the target thumbnail height is `target`.
+/// Scaling a row beyond `max_row_height` away from the target is heavily penalized so the
+/// shortest-path search avoids rows that would be distorted too far, even if that minimizes
+/// the squared-distance term on its own.
+fn row_badness(height: f32, target: f32, max_row_height: f32) -> f32 {
+    let diff = height - target;
+    let mut cost = diff * diff;
+    if (height - target).abs() > max_row_height {
+        cost += 1e9;
+    }
+    cost
+}
+
+/// Turns each item's ideal floating-point width into an integer width, such that the row's
+/// widths plus `(len - 1) * padding` sum up to exactly `container_width`. Every width is first
+/// floored, then the leftover pixels lost to flooring are handed out one at a time to the items
+/// with the largest fractional part (the largest-remainder method), so no single item absorbs
+/// all of the rounding error. Pixels are only ever added: if the floored widths already exceed
+/// the target, they are returned unchanged and the row overshoots `container_width`.
+fn distribute_row_widths(natural_widths: &[f32], container_width: u32, padding: u32) -> Vec<u16> {
+    let n = natural_widths.len();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let gaps = (n - 1) as u32;
+    let target_widths_sum = container_width.saturating_sub(gaps * padding);
+
+    let mut widths: Vec<u16> = natural_widths.iter().map(|&w| w.max(0.0) as u16).collect();
+    let floored_sum: u32 = widths.iter().map(|&w| w as u32).sum();
+    let remainder = target_widths_sum.saturating_sub(floored_sum);
+
+    // `remainder` can exceed `n` when the row height used to compute `natural_widths` doesn't
+    // exactly fill the row, so every item is systematically short by more than a pixel. Share
+    // the shortfall evenly first, then hand out the last few pixels by largest remaining
+    // fractional part.
+    let (even_share, leftover) = (remainder / n as u32, (remainder % n as u32) as usize);
+    for width in widths.iter_mut() {
+        *width += even_share as u16;
+    }
+
+    let mut by_fraction: Vec<usize> = (0..n).collect();
+    by_fraction.sort_by(|&a, &b| {
+        let frac_a = natural_widths[a] - natural_widths[a].floor();
+        let frac_b = natural_widths[b] - natural_widths[b].floor();
+        frac_b.partial_cmp(&frac_a).unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    for &index in by_fraction.iter().take(leftover) {
+        widths[index] += 1;
+    }
+
+    widths
+}
+
+/// Converts a row's widths into gap-free `left` offsets, i.e. the running sum of the preceding
+/// widths and paddings.
+fn positions_from_widths(widths: &[u16], padding: u32) -> Vec<u16> {
+    let mut lefts = Vec::with_capacity(widths.len());
+    let mut left = 0u32;
+    for &width in widths {
+        lefts.push(left as u16);
+        left += width as u32 + padding;
+    }
+    lefts
+}
+
+/// Splits `container_width` into `n_columns` column widths (and their `left` offsets) using the
+/// largest-remainder method, so the widths sum up to exactly `container_width`.
+fn distribute_column_widths(container_width: u32, n_columns: u32) -> (Vec<u16>, Vec<u16>) {
+    let base_col_width = container_width / n_columns;
+    let remainder = (container_width - base_col_width * n_columns) as usize;
+    let mut col_widths = vec![base_col_width as u16; n_columns as usize];
+    for col_width in col_widths.iter_mut().take(remainder) {
+        *col_width += 1;
+    }
+
+    let mut col_lefts = vec![0u16; n_columns as usize];
+    let mut left_acc = 0u32;
+    for (col, &width) in col_widths.iter().enumerate() {
+        col_lefts[col] = left_acc as u16;
+        left_acc += width as u32;
+    }
+
+    (col_widths, col_lefts)
+}
+
+/// Centers a crop of `src_width x src_height` that matches `target_ar` (width / height),
+/// cropping whichever dimension is too long for `target_ar` symmetrically and leaving the other
+/// dimension untouched.
+fn centered_crop(src_width: u16, src_height: u16, target_ar: f32) -> (u16, u16, u16, u16) {
+    if src_width == 0 || src_height == 0 || target_ar <= 0.0 {
+        return (0, 0, src_width, src_height);
+    }
+
+    let src_ar = src_width as f32 / src_height as f32;
+    if src_ar > target_ar {
+        let crop_width = ((target_ar * src_height as f32).round() as u16).min(src_width);
+        let crop_left = (src_width - crop_width) / 2;
+        (crop_left, 0, crop_width, src_height)
+    } else {
+        let crop_height = ((src_width as f32 / target_ar).round() as u16).min(src_height);
+        let crop_top = (src_height - crop_height) / 2;
+        (0, crop_top, src_width, crop_height)
+    }
 }
 
 fn index_of_max(values: &[i32]) -> Option<usize> {
@@ -207,3 +881,152 @@ fn index_of_min(values: &[i32]) -> Option<usize> {
 // Main idea:
 // - Take in a list of image dimensions, and a base thumbnail size (e.g. S, M, L)
 // - Output a list of image positions, laid out in a masonry format
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A zero `src_height` (the state every item starts in before `set_item_input` runs, or a
+    /// corrupt source image) used to be able to make `aspect_ratios[k] = inf`, which poisoned
+    /// every row sum it was folded into. Since every path back to `best[0] = 0` has to start with
+    /// a row spanning index 0, that left `best[i]` infinite for every `i`, not just the rows
+    /// touching the bad item — `break_at` silently stayed at its default and the whole list
+    /// collapsed into one row. Guard against that: a zero-height item anywhere in the list must
+    /// not stop the rest of the list from being broken into multiple rows.
+    #[test]
+    fn compute_justified_optimal_zero_height_item_does_not_poison_later_rows() {
+        let mut layout = Layout::new(20, 200);
+        for i in 0..20 {
+            if i == 0 {
+                layout.set_item_input(i, 200, 0);
+            } else {
+                layout.set_item_input(i, 200, 200);
+            }
+        }
+
+        layout.compute_justified_optimal(1000, 10, 400);
+
+        let tops: Vec<u16> = layout.items.iter().map(|item| item.top).collect();
+        let distinct_tops = tops.iter().collect::<std::collections::HashSet<_>>().len();
+        assert!(
+            distinct_tops > 1,
+            "expected multiple rows, but every item landed at the same top: {:?}",
+            tops
+        );
+    }
+
+    /// `fill_justified_rows` (used by `compute`) must close a row with its last item's
+    /// row-relative right edge landing exactly on `container_width`, not a ragged gap (the
+    /// original bug) or an overshoot past it (the padding-convention regression this guards
+    /// against: `distribute_row_widths` targets `container_width - (n - 1) * padding`, so the
+    /// row-height solve above it has to use the same "n - 1 gaps" convention or the two disagree).
+    #[test]
+    fn fill_justified_rows_last_item_right_edge_matches_container_width() {
+        let mut layout = Layout::new(14, 228);
+        for i in 0..14 {
+            layout.set_item_input(i, 228, 228);
+        }
+
+        layout.compute(1439, 15);
+
+        // Find the first row boundary: the run of items sharing the smallest `top`.
+        let first_top = layout.items[0].top;
+        let last_in_row = layout
+            .items
+            .iter()
+            .take_while(|item| item.top == first_top)
+            .last()
+            .unwrap();
+        let right_edge = last_in_row.left as u32 + last_in_row.width as u32;
+        assert_eq!(right_edge, 1439);
+    }
+
+    /// `row_bounds` (the cheap estimate pass `compute_range` uses to find which rows overlap the
+    /// viewport) and `fill_justified_rows` (the exact pass that actually lays out those rows) must
+    /// agree on every row's height, or `compute_range` drifts further from the real geometry with
+    /// every row and its reported total height is simply wrong. Both now solve row height via the
+    /// shared `solve_row_height` helper, so a full-viewport `compute_range` must land on exactly
+    /// the same row `top`s (and total height) as `compute`.
+    #[test]
+    fn compute_range_row_tops_match_compute() {
+        let mut via_compute = Layout::new(24, 200);
+        let mut via_range = Layout::new(24, 200);
+        for i in 0..24 {
+            via_compute.set_item_input(i, 300, 200);
+            via_range.set_item_input(i, 300, 200);
+        }
+
+        let compute_total = via_compute.compute(1000, 10);
+        let range_total = via_range.compute_range(1000, 10, 0, u32::MAX / 2);
+
+        assert_eq!(compute_total, range_total);
+        let compute_tops: Vec<u16> = via_compute.items.iter().map(|item| item.top).collect();
+        let range_tops: Vec<u16> = via_range.items.iter().map(|item| item.top).collect();
+        assert_eq!(compute_tops, range_tops);
+    }
+
+    /// A zero `src_height` item (every item's state before `set_item_input` runs) used to make
+    /// `row_bounds`' unguarded `base_row_height / src_height` divide out to `inf`, which saturated
+    /// to `u32::MAX` and then panicked on overflow at the next `+ padding`. `compute_range` must
+    /// survive a zero-height item without panicking.
+    #[test]
+    fn compute_range_zero_height_item_does_not_panic() {
+        let mut layout = Layout::new(5, 200);
+        layout.set_item_input(0, 200, 0);
+        for i in 1..5 {
+            layout.set_item_input(i, 200, 200);
+        }
+
+        layout.compute_range(1000, 10, 0, 500);
+    }
+
+    /// An interior `section_starts` entry past `self.items.len()` (a stale date-bucket boundary
+    /// relative to the current item count) used to be passed straight through as the previous
+    /// section's unclamped `end`, so `fill_justified_rows` indexed past the end of `self.items`
+    /// and panicked. `compute_sectioned` must survive a stale `section_starts` without panicking.
+    #[test]
+    fn compute_sectioned_out_of_range_section_start_does_not_panic() {
+        let mut layout = Layout::new(10, 200);
+        for i in 0..10 {
+            layout.set_item_input(i, 200, 200);
+        }
+
+        layout.compute_sectioned(1000, 10, &[0, 1000], 40);
+    }
+
+    /// The `FitStrategy::Contain` arm divides by `src_width` (the opposite denominator from the
+    /// row-breaking code's `src_height`), so a zero `src_width` item used to make `h` come out
+    /// `NaN`, which silently cast to a `0` cell height instead of the square fallback the rest of
+    /// the series adopted. `compute_vertical_fit` must give a zero-`src_width` item a non-zero
+    /// height.
+    #[test]
+    fn compute_vertical_fit_contain_zero_width_item_gets_nonzero_height() {
+        let mut layout = Layout::new(3, 200);
+        layout.set_item_input(0, 0, 300);
+        layout.set_item_input(1, 300, 300);
+        layout.set_item_input(2, 300, 300);
+
+        layout.compute_vertical_fit(600, 10, 0, 0, 0);
+
+        assert!(layout.items[0].height > 0);
+    }
+
+    /// Smoke test for the rebalancing pass: every item must still start inside the container
+    /// horizontally and above the reported max column height, across a list large enough for the
+    /// local-improvement loop to have something to do.
+    #[test]
+    fn compute_vertical_balanced_places_every_item_in_bounds() {
+        let mut layout = Layout::new(18, 200);
+        for i in 0..18 {
+            layout.set_item_input(i, 200 + (i as u16 % 5) * 40, 200);
+        }
+
+        let max_height = layout.compute_vertical_balanced(800, 10);
+
+        assert!(max_height > 0);
+        for item in layout.items.iter() {
+            assert!(item.left < 800);
+            assert!(item.top < max_height as u16);
+        }
+    }
+}